@@ -1,6 +1,11 @@
 use candid::{CandidType, Deserialize};
-use serde::Deserialize as SerdeDeserialize;
-use ic_llm::{ChatMessage, AssistantMessage, Model};
+use serde::{Deserialize as SerdeDeserialize, Serialize};
+use ic_llm::{AssistantMessage, ChatMessage, Model, ParameterType};
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
+use ic_stable_structures::storable::{Bound, Storable};
+use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap};
+use std::borrow::Cow;
+use std::cell::RefCell;
 
 #[derive(CandidType, Deserialize, Debug)]
 pub struct HttpRequest {
@@ -15,6 +20,21 @@ pub struct HttpResponse {
     pub status: u16,
     pub headers: Vec<(String, String)>,
     pub body: Vec<u8>,
+    /// Set by the query `http_request` to tell the HTTP gateway to replay
+    /// this request as an update call via `http_request_update` instead of
+    /// using this response. State changes made during a query are rolled
+    /// back once it returns, so any route that mutates stable memory must
+    /// signal this rather than write directly.
+    pub upgrade: Option<bool>,
+}
+
+fn upgrade_response() -> HttpResponse {
+    HttpResponse {
+        status: 200,
+        headers: vec![],
+        body: vec![],
+        upgrade: Some(true),
+    }
 }
 
 const SYSTEM_PROMPT: &str = r#"You are a helpful assistant.
@@ -22,35 +42,522 @@ Answer user questions clearly and concisely."#;
 
 const MODEL: Model = Model::Llama3_1_8B;
 
+/// Model ids the canister advertises via `GET /models`, and accepts in the
+/// `model` field of incoming payloads. Unknown or absent ids fall back to
+/// `MODEL`.
+const SUPPORTED_MODEL_IDS: &[&str] = &["llama3.1:8b", "qwen3:32b", "llama4-scout"];
+
+fn resolve_model(id: Option<&str>) -> Model {
+    match id {
+        Some("llama3.1:8b") => Model::Llama3_1_8B,
+        Some("qwen3:32b") => Model::Qwen3_32B,
+        Some("llama4-scout") => Model::Llama4Scout,
+        _ => MODEL,
+    }
+}
+
+type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+const SESSIONS_MEMORY_ID: MemoryId = MemoryId::new(0);
+const API_KEYS_MEMORY_ID: MemoryId = MemoryId::new(1);
+
+/// The message history for one `session_id`, kept in stable memory so it
+/// survives a canister upgrade.
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+struct StoredConversation {
+    messages: Vec<ChatMessage>,
+}
+
+impl Storable for StoredConversation {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(candid::encode_one(self).expect("failed to encode StoredConversation"))
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        candid::decode_one(&bytes).expect("failed to decode StoredConversation")
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        self.to_bytes().into_owned()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+thread_local! {
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+
+    static SESSIONS: RefCell<StableBTreeMap<String, StoredConversation, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(SESSIONS_MEMORY_ID)))
+    );
+
+    /// Allowlisted API keys, mapped to the time (nanos since epoch) they were added.
+    static API_KEYS: RefCell<StableBTreeMap<String, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(API_KEYS_MEMORY_ID)))
+    );
+}
+
+fn require_controller() -> Result<(), String> {
+    if ic_cdk::api::is_controller(&ic_cdk::api::caller()) {
+        Ok(())
+    } else {
+        Err("caller is not a controller".into())
+    }
+}
+
+#[ic_cdk::update]
+fn add_api_key(key: String) -> Result<(), String> {
+    require_controller()?;
+    API_KEYS.with(|k| k.borrow_mut().insert(key, ic_cdk::api::time()));
+    Ok(())
+}
+
+#[ic_cdk::update]
+fn revoke_api_key(key: String) -> Result<(), String> {
+    require_controller()?;
+    API_KEYS.with(|k| k.borrow_mut().remove(&key));
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn list_api_keys() -> Result<Vec<String>, String> {
+    require_controller()?;
+    Ok(API_KEYS.with(|k| k.borrow().keys().collect()))
+}
+
+fn bearer_token(headers: &[(String, String)]) -> Option<&str> {
+    headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("authorization"))
+        .and_then(|(_, value)| value.strip_prefix("Bearer "))
+        .map(str::trim)
+}
+
+fn is_authorized(headers: &[(String, String)]) -> bool {
+    match bearer_token(headers) {
+        Some(key) => API_KEYS.with(|k| k.borrow().contains_key(&key.to_string())),
+        None => false,
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct ApiErrorDetail {
+    message: String,
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+#[derive(Serialize, Debug)]
+struct ApiError {
+    error: ApiErrorDetail,
+}
+
+fn unauthorized_response() -> HttpResponse {
+    let body = ApiError {
+        error: ApiErrorDetail {
+            message: "Missing or invalid API key".into(),
+            kind: "invalid_request_error".into(),
+        },
+    };
+
+    HttpResponse {
+        status: 401,
+        headers: vec![
+            ("Content-Type".into(), "application/json".into()),
+            ("Access-Control-Allow-Origin".into(), "*".into()),
+        ],
+        body: serde_json::to_vec(&body).unwrap_or_default(),
+        upgrade: None,
+    }
+}
+
+fn load_session(session_id: &str) -> Vec<ChatMessage> {
+    SESSIONS.with(|s| s.borrow().get(&session_id.to_string()).map(|c| c.messages))
+        .unwrap_or_default()
+}
+
+fn save_session(session_id: String, messages: Vec<ChatMessage>) {
+    SESSIONS.with(|s| s.borrow_mut().insert(session_id, StoredConversation { messages }));
+}
+
+/// Converts an OpenAI-style JSON-object-as-string (e.g. `{"location":"Paris"}`)
+/// into the `[{"name": ..., "value": ...}]` shape `ic_llm`'s `ToolCallArgument`
+/// deserializes from. `ToolCallArgument` itself isn't a public path, so this
+/// is the only way to construct one from outside the crate.
+fn arguments_string_to_entries(raw: &str) -> Vec<serde_json::Value> {
+    let object = serde_json::from_str::<serde_json::Value>(raw)
+        .ok()
+        .and_then(|v| v.as_object().cloned())
+        .unwrap_or_default();
+
+    object
+        .into_iter()
+        .map(|(name, value)| {
+            let value = match value {
+                serde_json::Value::String(s) => s,
+                other => other.to_string(),
+            };
+            serde_json::json!({ "name": name, "value": value })
+        })
+        .collect()
+}
+
+fn reply_to_chat_message(reply: &AssistantReply) -> ChatMessage {
+    let tool_calls: Vec<serde_json::Value> = reply
+        .tool_calls
+        .iter()
+        .map(|tc| {
+            serde_json::json!({
+                "id": tc.id,
+                "function": {
+                    "name": tc.name,
+                    "arguments": arguments_string_to_entries(&tc.arguments),
+                },
+            })
+        })
+        .collect();
+
+    let assistant: AssistantMessage = serde_json::from_value(serde_json::json!({
+        "content": reply.content,
+        "tool_calls": tool_calls,
+    }))
+    .expect("AssistantReply always maps onto ic_llm::AssistantMessage");
+
+    ChatMessage::Assistant(assistant)
+}
+
+fn chat_message_role_content(message: &ChatMessage) -> (String, String) {
+    match message {
+        ChatMessage::System { content } => ("system".into(), content.clone()),
+        ChatMessage::User { content } => ("user".into(), content.clone()),
+        ChatMessage::Assistant(assistant) => {
+            ("assistant".into(), assistant.content.clone().unwrap_or_default())
+        }
+        ChatMessage::Tool { content, .. } => ("tool".into(), content.clone()),
+    }
+}
+
+#[ic_cdk::pre_upgrade]
+fn pre_upgrade() {
+    ic_cdk::println!("pre_upgrade: sessions live in stable memory, nothing to snapshot");
+}
+
+#[ic_cdk::post_upgrade]
+fn post_upgrade() {
+    ic_cdk::println!("post_upgrade: sessions restored from stable memory");
+}
+
+#[derive(SerdeDeserialize, Debug)]
+struct IncomingFunctionCall {
+    name: String,
+    arguments: String,
+}
+
+#[derive(SerdeDeserialize, Debug)]
+struct IncomingToolCall {
+    id: String,
+    function: IncomingFunctionCall,
+}
+
 #[derive(SerdeDeserialize, Debug)]
 struct IncomingMessage {
     role: String,
     content: String,
+    /// Present on "tool" messages so the reply can be correlated back to the
+    /// tool call that produced it.
+    tool_call_id: Option<String>,
+    /// Present on "assistant" messages that issued tool calls, so replaying
+    /// history keeps them correlated with the "tool" replies that follow.
+    tool_calls: Option<Vec<IncomingToolCall>>,
+}
+
+#[derive(SerdeDeserialize, Debug)]
+struct IncomingTool {
+    name: String,
+    description: String,
+    parameters: serde_json::Value,
 }
 
 #[derive(SerdeDeserialize, Debug)]
 struct IncomingPayload {
+    model: Option<String>,
     messages: Vec<IncomingMessage>,
+    tools: Option<Vec<IncomingTool>>,
+    session_id: Option<String>,
+    system: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct SessionMessage {
+    role: String,
+    content: String,
+}
+
+#[derive(Serialize, Debug)]
+struct SessionTranscript {
+    session_id: String,
+    messages: Vec<SessionMessage>,
+}
+
+/// Candid-facing tool definition passed into `chat()`. `parameters` is kept
+/// as a raw JSON Schema string rather than `serde_json::Value`, which has no
+/// Candid representation.
+#[derive(CandidType, Deserialize, Debug, Clone)]
+struct ToolDefinition {
+    name: String,
+    description: String,
+    parameters: String,
+}
+
+#[derive(CandidType, Serialize, Debug, Clone)]
+struct ToolCallReply {
+    id: String,
+    name: String,
+    arguments: String,
+}
+
+#[derive(CandidType, Serialize, Debug, Clone)]
+struct AssistantReply {
+    content: Option<String>,
+    tool_calls: Vec<ToolCallReply>,
+}
+
+/// Builds an `ic_llm::Tool` from a `ToolDefinition`'s JSON Schema
+/// `parameters` string. Only `object` schemas with flat `string`/`number`/
+/// `boolean` properties are understood, matching what `ic_llm::Parameters`
+/// itself can represent.
+fn tool_from_definition(def: ToolDefinition) -> ic_llm::Tool {
+    let mut builder = ic_llm::tool(def.name);
+    if !def.description.is_empty() {
+        builder = builder.with_description(def.description);
+    }
+
+    let schema: serde_json::Value = serde_json::from_str(&def.parameters).unwrap_or_default();
+    let required: Vec<&str> = schema
+        .get("required")
+        .and_then(|r| r.as_array())
+        .map(|values| values.iter().filter_map(|v| v.as_str()).collect())
+        .unwrap_or_default();
+
+    if let Some(properties) = schema.get("properties").and_then(|p| p.as_object()) {
+        for (name, prop) in properties {
+            let parameter_type = match prop.get("type").and_then(|t| t.as_str()) {
+                Some("boolean") => ParameterType::Boolean,
+                Some("number") | Some("integer") => ParameterType::Number,
+                _ => ParameterType::String,
+            };
+
+            let mut parameter = ic_llm::parameter(name.clone(), parameter_type);
+            if let Some(description) = prop.get("description").and_then(|d| d.as_str()) {
+                parameter = parameter.with_description(description);
+            }
+            if required.contains(&name.as_str()) {
+                parameter = parameter.is_required();
+            }
+
+            builder = builder.with_parameter(parameter);
+        }
+    }
+
+    builder.build()
+}
+
+fn to_tool_definitions(tools: Vec<IncomingTool>) -> Vec<ToolDefinition> {
+    tools
+        .into_iter()
+        .map(|t| ToolDefinition {
+            name: t.name,
+            description: t.description,
+            parameters: t.parameters.to_string(),
+        })
+        .collect()
+}
+
+fn incoming_tool_calls_to_assistant_message(
+    content: String,
+    tool_calls: Vec<IncomingToolCall>,
+) -> ChatMessage {
+    let tool_calls: Vec<serde_json::Value> = tool_calls
+        .into_iter()
+        .map(|tc| {
+            serde_json::json!({
+                "id": tc.id,
+                "function": {
+                    "name": tc.function.name,
+                    "arguments": arguments_string_to_entries(&tc.function.arguments),
+                },
+            })
+        })
+        .collect();
+
+    let assistant: AssistantMessage = serde_json::from_value(serde_json::json!({
+        "content": content,
+        "tool_calls": tool_calls,
+    }))
+    .expect("incoming assistant message always maps onto ic_llm::AssistantMessage");
+
+    ChatMessage::Assistant(assistant)
+}
+
+fn to_chat_messages(messages: Vec<IncomingMessage>) -> Vec<ChatMessage> {
+    messages
+        .into_iter()
+        .map(|m| match m.role.as_str() {
+            "system" => ChatMessage::System { content: m.content },
+            "assistant" => {
+                incoming_tool_calls_to_assistant_message(m.content, m.tool_calls.unwrap_or_default())
+            }
+            "tool" => ChatMessage::Tool {
+                content: m.content,
+                tool_call_id: m.tool_call_id.unwrap_or_default(),
+            },
+            _ => ChatMessage::User { content: m.content },
+        })
+        .collect()
+}
+
+#[derive(Serialize, Debug)]
+struct OpenAiModel {
+    id: String,
+    object: String,
+}
+
+#[derive(Serialize, Debug)]
+struct OpenAiModelList {
+    object: String,
+    data: Vec<OpenAiModel>,
+}
+
+/// `temperature` and `max_tokens` are accepted so the `/v1/chat/completions`
+/// contract doesn't silently reject (or silently ignore without saying so)
+/// fields every OpenAI client sends — but, like `GenerationOptions`, they
+/// aren't forwarded anywhere: the pinned `ic_llm::ChatBuilder` has no hooks
+/// for sampling parameters.
+#[derive(SerdeDeserialize, Debug)]
+struct OpenAiChatRequest {
+    model: Option<String>,
+    messages: Vec<IncomingMessage>,
+    tools: Option<Vec<IncomingTool>>,
+    system: Option<String>,
+    #[allow(dead_code)]
+    temperature: Option<f32>,
+    #[allow(dead_code)]
+    max_tokens: Option<u32>,
+    #[allow(dead_code)]
+    stream: Option<bool>,
+}
+
+#[derive(Serialize, Debug)]
+struct OpenAiToolCallFunction {
+    name: String,
+    arguments: String,
+}
+
+#[derive(Serialize, Debug)]
+struct OpenAiToolCall {
+    id: String,
+    #[serde(rename = "type")]
+    kind: String,
+    function: OpenAiToolCallFunction,
+}
+
+#[derive(Serialize, Debug)]
+struct OpenAiChatMessage {
+    role: String,
+    content: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_calls: Option<Vec<OpenAiToolCall>>,
+}
+
+#[derive(Serialize, Debug)]
+struct OpenAiChoice {
+    index: u32,
+    message: OpenAiChatMessage,
+    finish_reason: String,
+}
+
+#[derive(Serialize, Debug)]
+struct OpenAiUsage {
+    prompt_tokens: u32,
+    completion_tokens: u32,
+    total_tokens: u32,
+}
+
+#[derive(Serialize, Debug)]
+struct OpenAiChatResponse {
+    id: String,
+    object: String,
+    created: u64,
+    model: String,
+    choices: Vec<OpenAiChoice>,
+    usage: OpenAiUsage,
+}
+
+fn word_count(text: &str) -> u32 {
+    text.split_whitespace().count() as u32
+}
+
+/// Generation controls threaded into the `ic_llm` chat builder. `system`
+/// left as `None` falls back to `SYSTEM_PROMPT`.
+///
+/// `ic_llm::ChatBuilder` (pinned version) has no hooks for sampling
+/// parameters such as temperature, max tokens or a seed — it only accepts
+/// messages and tools — so those aren't modeled here. If a future `ic_llm`
+/// release exposes them, thread them through the same way as `system`.
+#[derive(CandidType, Deserialize, Debug, Default)]
+struct GenerationOptions {
+    model: Option<String>,
+    tools: Option<Vec<ToolDefinition>>,
+    system: Option<String>,
 }
 
 #[ic_cdk::update]
-async fn chat(messages: Vec<ChatMessage>) -> String {
+async fn chat(messages: Vec<ChatMessage>, options: GenerationOptions) -> AssistantReply {
     ic_cdk::println!("chat() called with {} messages", messages.len());
 
+    let system_prompt = options.system.unwrap_or_else(|| SYSTEM_PROMPT.to_string());
     let mut all_messages = vec![ChatMessage::System {
-        content: SYSTEM_PROMPT.to_string(),
+        content: system_prompt,
     }];
     all_messages.extend(messages);
 
-    let chat = ic_llm::chat(MODEL).with_messages(all_messages);
+    let mut chat =
+        ic_llm::chat(resolve_model(options.model.as_deref())).with_messages(all_messages);
+
+    if let Some(tools) = options.tools.filter(|tools| !tools.is_empty()) {
+        let llm_tools = tools.into_iter().map(tool_from_definition).collect();
+        chat = chat.with_tools(llm_tools);
+    }
 
     ic_cdk::println!("Sending request to LLM canister…");
     let response = chat.send().await;
     ic_cdk::println!("LLM canister replied: {:?}", response);
 
-    let text = response.message.content.unwrap_or_default();
-    ic_cdk::println!("Returning text: {}", text);
-    text
+    let tool_calls = response
+        .message
+        .tool_calls
+        .into_iter()
+        .map(|tc| {
+            let arguments: serde_json::Map<String, serde_json::Value> = tc
+                .function
+                .arguments
+                .into_iter()
+                .map(|arg| (arg.name, serde_json::Value::String(arg.value)))
+                .collect();
+
+            ToolCallReply {
+                id: tc.id,
+                name: tc.function.name,
+                arguments: serde_json::Value::Object(arguments).to_string(),
+            }
+        })
+        .collect();
+
+    AssistantReply {
+        content: response.message.content,
+        tool_calls,
+    }
 }
 
 
@@ -66,68 +573,101 @@ async fn http_request(req: HttpRequest) -> HttpResponse {
 
     ic_cdk::println!("Raw body: {}", String::from_utf8_lossy(&req.body));
 
+    let method = req.method.to_uppercase();
+
     // Handle preflight CORS
-    if req.method.to_uppercase() == "OPTIONS" {
+    if method == "OPTIONS" {
         return HttpResponse {
             status: 204,
             headers: vec![
                 ("Access-Control-Allow-Origin".into(), "*".into()),
                 (
                     "Access-Control-Allow-Methods".into(),
-                    "POST, OPTIONS".into(),
+                    "GET, POST, DELETE, OPTIONS".into(),
                 ),
                 (
                     "Access-Control-Allow-Headers".into(),
-                    "Content-Type".into(),
+                    "Content-Type, Authorization".into(),
                 ),
             ],
             body: vec![],
+            upgrade: None,
         };
     }
 
-    if req.method.to_uppercase() == "POST" && req.url.starts_with("/chat") {
-        match serde_json::from_slice::<IncomingPayload>(&req.body) {
-            Ok(payload) => {
-                ic_cdk::println!("Parsed JSON: {:?}", payload);
-
-                let mut all_messages = Vec::new();
-                for m in payload.messages {
-                    match m.role.as_str() {
-                        "system" => all_messages.push(ChatMessage::System { content: m.content }),
-                        "assistant" => all_messages.push(ChatMessage::Assistant(AssistantMessage {
-                            content: Some(m.content),
-                            tool_calls: vec![],
-                        })),
-                        "tool" => all_messages.push(ChatMessage::Tool {
-                            content: m.content,
-                            tool_call_id: "".into(),
-                        }),
-                        _ => all_messages.push(ChatMessage::User { content: m.content }),
-                    }
-                }
+    // `POST /chat` may persist a session, `DELETE /sessions/{id}` always
+    // removes one, and `POST /v1/chat/completions` calls the LLM canister —
+    // all three mutate stable memory or make an inter-canister update call,
+    // neither of which a query can do (state changes roll back, and update
+    // calls from a query trap). Hand these off to `http_request_update` so
+    // they actually work.
+    if (method == "POST" && (req.url.starts_with("/chat") || req.url.starts_with("/v1/chat/completions")))
+        || (method == "DELETE" && req.url.starts_with("/sessions/"))
+    {
+        return upgrade_response();
+    }
 
-                let reply_text = chat(all_messages).await;
+    if method == "GET" && req.url.starts_with("/models") {
+        let response = OpenAiModelList {
+            object: "list".into(),
+            data: SUPPORTED_MODEL_IDS
+                .iter()
+                .map(|id| OpenAiModel {
+                    id: id.to_string(),
+                    object: "model".into(),
+                })
+                .collect(),
+        };
 
-                return HttpResponse {
-                    status: 200,
-                    headers: vec![
-                        ("Content-Type".into(), "text/plain".into()),
-                        ("Access-Control-Allow-Origin".into(), "*".into()),
-                    ],
-                    body: reply_text.into_bytes(),
-                };
-            }
-            Err(e) => {
-                ic_cdk::println!("JSON parse error: {}", e);
-                return HttpResponse {
-                    status: 400,
+        return HttpResponse {
+            status: 200,
+            headers: vec![
+                ("Content-Type".into(), "application/json".into()),
+                ("Access-Control-Allow-Origin".into(), "*".into()),
+            ],
+            body: serde_json::to_vec(&response).unwrap_or_default(),
+            upgrade: None,
+        };
+    }
+
+    if method == "GET" {
+        if let Some(session_id) = req.url.strip_prefix("/sessions/") {
+            let session_id = session_id.split('?').next().unwrap_or("").to_string();
+            let stored = SESSIONS.with(|s| s.borrow().get(&session_id));
+            return match stored {
+                Some(conversation) => {
+                    let transcript = SessionTranscript {
+                        session_id,
+                        messages: conversation
+                            .messages
+                            .iter()
+                            .map(|m| {
+                                let (role, content) = chat_message_role_content(m);
+                                SessionMessage { role, content }
+                            })
+                            .collect(),
+                    };
+
+                    HttpResponse {
+                        status: 200,
+                        headers: vec![
+                            ("Content-Type".into(), "application/json".into()),
+                            ("Access-Control-Allow-Origin".into(), "*".into()),
+                        ],
+                        body: serde_json::to_vec(&transcript).unwrap_or_default(),
+                        upgrade: None,
+                    }
+                }
+                None => HttpResponse {
+                    status: 404,
                     headers: vec![
                         ("Content-Type".into(), "text/plain".into()),
                         ("Access-Control-Allow-Origin".into(), "*".into()),
                     ],
-                    body: format!("Invalid JSON: {e}").into_bytes(),
-                };
-            }
+                    body: b"Session not found".to_vec(),
+                    upgrade: None,
+                },
+            };
         }
     }
 
@@ -139,5 +679,216 @@ async fn http_request(req: HttpRequest) -> HttpResponse {
             ("Access-Control-Allow-Origin".into(), "*".into()),
         ],
         body: b"Not Found".to_vec(),
+        upgrade: None,
+    }
+}
+
+/// Re-entry point for routes that mutate stable memory or call out to the
+/// LLM canister. The HTTP gateway calls this as an update after
+/// `http_request` signals `upgrade: Some(true)` for `POST /chat`,
+/// `POST /v1/chat/completions`, and `DELETE /sessions/{id}`, so writes made
+/// here persist and inter-canister calls made here actually work.
+#[ic_cdk::update]
+async fn http_request_update(req: HttpRequest) -> HttpResponse {
+    let method = req.method.to_uppercase();
+
+    if method == "POST" && req.url.starts_with("/chat") {
+        if !is_authorized(&req.headers) {
+            return unauthorized_response();
+        }
+
+        return handle_chat(&req.body).await;
+    }
+
+    if method == "POST" && req.url.starts_with("/v1/chat/completions") {
+        if !is_authorized(&req.headers) {
+            return unauthorized_response();
+        }
+
+        return handle_openai_chat(&req.body).await;
+    }
+
+    if method == "DELETE" {
+        if let Some(session_id) = req.url.strip_prefix("/sessions/") {
+            let session_id = session_id.split('?').next().unwrap_or("").to_string();
+            let existed = SESSIONS.with(|s| s.borrow_mut().remove(&session_id)).is_some();
+            return HttpResponse {
+                status: if existed { 200 } else { 404 },
+                headers: vec![
+                    ("Content-Type".into(), "text/plain".into()),
+                    ("Access-Control-Allow-Origin".into(), "*".into()),
+                ],
+                body: if existed {
+                    b"Session deleted".to_vec()
+                } else {
+                    b"Session not found".to_vec()
+                },
+                upgrade: None,
+            };
+        }
+    }
+
+    ic_cdk::println!("No matching update route for {} {}", req.method, req.url);
+    HttpResponse {
+        status: 404,
+        headers: vec![
+            ("Content-Type".into(), "text/plain".into()),
+            ("Access-Control-Allow-Origin".into(), "*".into()),
+        ],
+        body: b"Not Found".to_vec(),
+        upgrade: None,
+    }
+}
+
+async fn handle_chat(body: &[u8]) -> HttpResponse {
+    match serde_json::from_slice::<IncomingPayload>(body) {
+        Ok(payload) => {
+            ic_cdk::println!("Parsed JSON: {:?}", payload);
+
+            let mut history = payload
+                .session_id
+                .as_deref()
+                .map(load_session)
+                .unwrap_or_default();
+            history.extend(to_chat_messages(payload.messages));
+            let options = GenerationOptions {
+                model: payload.model,
+                tools: payload.tools.map(to_tool_definitions),
+                system: payload.system,
+            };
+            let reply = chat(history.clone(), options).await;
+
+            if let Some(session_id) = payload.session_id {
+                history.push(reply_to_chat_message(&reply));
+                save_session(session_id, history);
+            }
+
+            if reply.tool_calls.is_empty() {
+                return HttpResponse {
+                    status: 200,
+                    headers: vec![
+                        ("Content-Type".into(), "text/plain".into()),
+                        ("Access-Control-Allow-Origin".into(), "*".into()),
+                    ],
+                    body: reply.content.unwrap_or_default().into_bytes(),
+                    upgrade: None,
+                };
+            }
+
+            // Tool calls can't be carried in a text/plain body, so fall
+            // back to a small JSON envelope instead of discarding them.
+            HttpResponse {
+                status: 200,
+                headers: vec![
+                    ("Content-Type".into(), "application/json".into()),
+                    ("Access-Control-Allow-Origin".into(), "*".into()),
+                ],
+                body: serde_json::to_vec(&reply).unwrap_or_default(),
+                upgrade: None,
+            }
+        }
+        Err(e) => {
+            ic_cdk::println!("JSON parse error: {}", e);
+            HttpResponse {
+                status: 400,
+                headers: vec![
+                    ("Content-Type".into(), "text/plain".into()),
+                    ("Access-Control-Allow-Origin".into(), "*".into()),
+                ],
+                body: format!("Invalid JSON: {e}").into_bytes(),
+                upgrade: None,
+            }
+        }
+    }
+}
+
+async fn handle_openai_chat(body: &[u8]) -> HttpResponse {
+    match serde_json::from_slice::<OpenAiChatRequest>(body) {
+        Ok(payload) => {
+            ic_cdk::println!("Parsed OpenAI-compatible request: {:?}", payload);
+
+            let model_name = payload.model.clone().unwrap_or_else(|| "llama3.1:8b".into());
+            let prompt_tokens: u32 = payload
+                .messages
+                .iter()
+                .map(|m| word_count(&m.content))
+                .sum();
+
+            let all_messages = to_chat_messages(payload.messages);
+            let options = GenerationOptions {
+                model: payload.model,
+                tools: payload.tools.map(to_tool_definitions),
+                system: payload.system,
+            };
+            let reply = chat(all_messages, options).await;
+            let completion_tokens = word_count(reply.content.as_deref().unwrap_or_default());
+
+            let finish_reason = if reply.tool_calls.is_empty() {
+                "stop"
+            } else {
+                "tool_calls"
+            };
+            let tool_calls = if reply.tool_calls.is_empty() {
+                None
+            } else {
+                Some(
+                    reply
+                        .tool_calls
+                        .into_iter()
+                        .map(|tc| OpenAiToolCall {
+                            id: tc.id,
+                            kind: "function".into(),
+                            function: OpenAiToolCallFunction {
+                                name: tc.name,
+                                arguments: tc.arguments,
+                            },
+                        })
+                        .collect(),
+                )
+            };
+
+            let response = OpenAiChatResponse {
+                id: format!("chatcmpl-{}", ic_cdk::api::time()),
+                object: "chat.completion".into(),
+                created: ic_cdk::api::time() / 1_000_000_000,
+                model: model_name,
+                choices: vec![OpenAiChoice {
+                    index: 0,
+                    message: OpenAiChatMessage {
+                        role: "assistant".into(),
+                        content: reply.content,
+                        tool_calls,
+                    },
+                    finish_reason: finish_reason.into(),
+                }],
+                usage: OpenAiUsage {
+                    prompt_tokens,
+                    completion_tokens,
+                    total_tokens: prompt_tokens + completion_tokens,
+                },
+            };
+
+            HttpResponse {
+                status: 200,
+                headers: vec![
+                    ("Content-Type".into(), "application/json".into()),
+                    ("Access-Control-Allow-Origin".into(), "*".into()),
+                ],
+                body: serde_json::to_vec(&response).unwrap_or_default(),
+                upgrade: None,
+            }
+        }
+        Err(e) => {
+            ic_cdk::println!("JSON parse error: {}", e);
+            HttpResponse {
+                status: 400,
+                headers: vec![
+                    ("Content-Type".into(), "text/plain".into()),
+                    ("Access-Control-Allow-Origin".into(), "*".into()),
+                ],
+                body: format!("Invalid JSON: {e}").into_bytes(),
+                upgrade: None,
+            }
+        }
     }
 }